@@ -0,0 +1,317 @@
+//! A caching wrapper around [`Client`] for queries that are immutable once
+//! resolved against a specific, finalized block.
+//!
+//! Queries such as [`Client::get_account_info`] or [`Client::get_module_list`]
+//! never change their answer for a fixed block hash once that block is
+//! finalized, so repeatedly asking the node for the same `(block, query)`
+//! pair is wasted round-trips. [`CachingClient`] memoizes such answers in a
+//! bounded, sharded cache, and only ever inserts entries that were resolved
+//! against an explicitly [`Given`](BlockIdentifier::Given) block hash that
+//! is confirmed, by checking the last [`FINALITY_CHECK_LOOKBACK`] ancestors
+//! of the last finalized block, to actually be finalized; it never caches
+//! against [`Best`](BlockIdentifier::Best) or
+//! [`LastFinal`](BlockIdentifier::LastFinal) (since those move from call to
+//! call), nor against a `Given` block that isn't found to be finalized
+//! within the lookback window.
+//!
+//! Module source is not cached here: this `Client` does not expose a
+//! `get_module_source` endpoint in this tree, so there is nothing to wrap.
+//! The cache key space (`CacheKey`) is laid out so adding it later, once a
+//! `Client::get_module_source` exists, is a matter of adding a variant and
+//! a wrapping method analogous to [`CachingClient::get_module_list`].
+//!
+//! The cache can be bounded either by entry count ([`CachingClient::new`])
+//! or by estimated in-memory bytes
+//! ([`CachingClient::with_weighted_capacity`]) — the latter matters because
+//! `AccountInfo` values vary widely in size and a fixed entry count doesn't
+//! stop a handful of large accounts from dominating memory.
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use futures::StreamExt;
+use quick_cache::{sync::Cache, Weighter};
+
+use crate::types::{hashes::BlockHash, smart_contracts::ModuleRef, AccountInfo};
+
+use super::{AccountIdentifier, BlockIdentifier, Client, QueryResponse};
+
+/// Default number of entries kept in the cache when none is specified via
+/// [`CachingClient::new`].
+pub const DEFAULT_CACHE_CAPACITY: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum CacheKey {
+    AccountInfo {
+        block: BlockHash,
+        account: AccountIdentifierKey,
+    },
+    ModuleList {
+        block: BlockHash,
+    },
+}
+
+/// A hashable, owned stand-in for [`AccountIdentifier`], used only as a cache
+/// key. Queries are keyed on the bytes the node would use to identify the
+/// account, so `Address`, `CredId`, and `Index` lookups for the same account
+/// are cached separately (they are, after all, distinct requests).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum AccountIdentifierKey {
+    Address(Vec<u8>),
+    CredId(Vec<u8>),
+    Index(u64),
+}
+
+impl From<&AccountIdentifier> for AccountIdentifierKey {
+    fn from(ai: &AccountIdentifier) -> Self {
+        match ai {
+            AccountIdentifier::Address(addr) => Self::Address(crypto_common::to_bytes(addr)),
+            AccountIdentifier::CredId(cred) => Self::CredId(crypto_common::to_bytes(cred)),
+            AccountIdentifier::Index(idx) => Self::Index((*idx).into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum CacheValue {
+    AccountInfo(AccountInfo),
+    ModuleList(Arc<[ModuleRef]>),
+}
+
+/// Weighs a [`CacheValue`] either as a single unit (so `capacity` bounds
+/// the number of entries, [`CachingClient::new`]'s behaviour) or as its
+/// estimated serialized size in bytes (so `capacity` bounds total
+/// estimated memory, [`CachingClient::with_weighted_capacity`]'s
+/// behaviour).
+#[derive(Debug, Clone, Copy)]
+enum CacheWeigher {
+    Entries,
+    Bytes,
+}
+
+impl Weighter<CacheKey, CacheValue> for CacheWeigher {
+    fn weight(&self, _key: &CacheKey, value: &CacheValue) -> u64 {
+        match self {
+            CacheWeigher::Entries => 1,
+            CacheWeigher::Bytes => match value {
+                CacheValue::AccountInfo(info) => crypto_common::to_bytes(info).len() as u64,
+                CacheValue::ModuleList(modules) => {
+                    (modules.len() * std::mem::size_of::<ModuleRef>()) as u64
+                }
+            }
+            .max(1),
+        }
+    }
+}
+
+/// Counters tracking how effective the cache has been, exposed via
+/// [`CachingClient::metrics`].
+#[derive(Debug, Default)]
+pub struct CacheMetrics {
+    hits:   AtomicU64,
+    misses: AtomicU64,
+}
+
+/// A snapshot of [`CacheMetrics`] at a point in time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheMetricsSnapshot {
+    pub hits:    u64,
+    pub misses:  u64,
+    pub entries: usize,
+}
+
+impl CacheMetrics {
+    fn record_hit(&self) { self.hits.fetch_add(1, Ordering::Relaxed); }
+
+    fn record_miss(&self) { self.misses.fetch_add(1, Ordering::Relaxed); }
+}
+
+/// Number of ancestors of the last finalized block checked by
+/// [`is_finalized`]. Bounds the cost of the finality check to a single,
+/// fixed-size request instead of a walk back to genesis: a `Given` block
+/// more than this many heights behind the last finalized block is treated
+/// as not (yet confirmed) finalized and simply isn't cached, trading a
+/// missed cache opportunity for a bounded, predictable RPC cost on every
+/// miss.
+const FINALITY_CHECK_LOOKBACK: u64 = 1024;
+
+/// Whether `block` is the last finalized block or one of its last
+/// [`FINALITY_CHECK_LOOKBACK`] ancestors. Since finalization only ever moves
+/// forward along a single chain, finding `block` here is sufficient to
+/// establish that it is finalized and will never change.
+async fn is_finalized(client: &mut Client, block: BlockHash) -> crate::endpoints::QueryResult<bool> {
+    // `get_ancestors` returns the given block itself followed by its
+    // ancestors, hence the `+ 1`.
+    let response = client
+        .get_ancestors(&BlockIdentifier::LastFinal, FINALITY_CHECK_LOOKBACK + 1)
+        .await?;
+    let ancestors: Vec<BlockHash> = response.response.collect::<Vec<_>>().await.into_iter().collect::<Result<_, _>>()?;
+    Ok(contains_finalized(&ancestors, block))
+}
+
+/// Whether `block` appears among `ancestors`, the decision `is_finalized`
+/// makes once it has the (bounded) ancestor window in hand.
+fn contains_finalized(ancestors: &[BlockHash], block: BlockHash) -> bool {
+    ancestors.iter().any(|hash| *hash == block)
+}
+
+/// A [`Client`] wrapper that transparently caches responses to immutable,
+/// block-scoped queries.
+///
+/// Cloning a `CachingClient` is cheap: the underlying connection and the
+/// cache are both shared (the cache via an `Arc`), mirroring the cloning
+/// behaviour of [`Client`] itself.
+#[derive(Clone, Debug)]
+pub struct CachingClient {
+    client:  Client,
+    cache:   Arc<Cache<CacheKey, CacheValue, CacheWeigher>>,
+    metrics: Arc<CacheMetrics>,
+}
+
+impl CachingClient {
+    /// Construct a new caching client wrapping `client`, with a cache that
+    /// holds at most `capacity` entries, regardless of their size. Use
+    /// [`Self::with_weighted_capacity`] instead to bound the cache by
+    /// estimated memory rather than entry count.
+    pub fn new(client: Client, capacity: usize) -> Self {
+        Self {
+            client,
+            cache: Arc::new(Cache::with_weighter(capacity, capacity as u64, CacheWeigher::Entries)),
+            metrics: Arc::new(CacheMetrics::default()),
+        }
+    }
+
+    /// Construct a new caching client with [`DEFAULT_CACHE_CAPACITY`].
+    pub fn with_default_capacity(client: Client) -> Self { Self::new(client, DEFAULT_CACHE_CAPACITY) }
+
+    /// Construct a new caching client bounded by estimated in-memory bytes
+    /// rather than entry count: `estimated_items_capacity` sizes the
+    /// cache's internal sharding the way `capacity` does for [`Self::new`],
+    /// while `weight_capacity` bounds the total estimated serialized size
+    /// (in bytes) of cached values. Prefer this over [`Self::new`] when
+    /// cached values (chiefly `AccountInfo`) vary widely in size, since a
+    /// few large accounts can otherwise consume far more memory than a
+    /// fixed entry count suggests.
+    pub fn with_weighted_capacity(
+        client: Client,
+        estimated_items_capacity: usize,
+        weight_capacity: u64,
+    ) -> Self {
+        Self {
+            client,
+            cache: Arc::new(Cache::with_weighter(
+                estimated_items_capacity,
+                weight_capacity,
+                CacheWeigher::Bytes,
+            )),
+            metrics: Arc::new(CacheMetrics::default()),
+        }
+    }
+
+    /// Drop all cached entries. Does not reset the hit/miss counters.
+    pub fn clear(&self) { self.cache.clear(); }
+
+    /// A snapshot of the cache's hit/miss counters and its current size.
+    pub fn metrics(&self) -> CacheMetricsSnapshot {
+        CacheMetricsSnapshot {
+            hits:    self.metrics.hits.load(Ordering::Relaxed),
+            misses:  self.metrics.misses.load(Ordering::Relaxed),
+            entries: self.cache.len(),
+        }
+    }
+
+    /// Access the wrapped [`Client`] directly, bypassing the cache. Useful
+    /// for queries that are not (yet) cached by this type.
+    pub fn inner(&self) -> &Client { &self.client }
+
+    /// Access the wrapped [`Client`] mutably, bypassing the cache.
+    pub fn inner_mut(&mut self) -> &mut Client { &mut self.client }
+
+    pub async fn get_account_info(
+        &mut self,
+        acc: &AccountIdentifier,
+        bi: &BlockIdentifier,
+    ) -> crate::endpoints::QueryResult<QueryResponse<AccountInfo>> {
+        if let BlockIdentifier::Given(block) = bi {
+            let key = CacheKey::AccountInfo {
+                block:   *block,
+                account: acc.into(),
+            };
+            if let Some(CacheValue::AccountInfo(response)) = self.cache.get(&key) {
+                self.metrics.record_hit();
+                return Ok(QueryResponse {
+                    block_hash: *block,
+                    response,
+                });
+            }
+            self.metrics.record_miss();
+            let result = self.client.get_account_info(acc, bi).await?;
+            if is_finalized(&mut self.client, *block).await? {
+                self.cache
+                    .insert(key, CacheValue::AccountInfo(result.response.clone()));
+            }
+            Ok(result)
+        } else {
+            self.client.get_account_info(acc, bi).await
+        }
+    }
+
+    pub async fn get_module_list(
+        &mut self,
+        bi: &BlockIdentifier,
+    ) -> crate::endpoints::QueryResult<QueryResponse<Vec<ModuleRef>>> {
+        if let BlockIdentifier::Given(block) = bi {
+            let key = CacheKey::ModuleList { block: *block };
+            if let Some(CacheValue::ModuleList(modules)) = self.cache.get(&key) {
+                self.metrics.record_hit();
+                return Ok(QueryResponse {
+                    block_hash: *block,
+                    response:   modules.to_vec(),
+                });
+            }
+            self.metrics.record_miss();
+            let result = self.client.get_module_list(bi).await?;
+            let modules: Vec<ModuleRef> = result.response.collect::<Result<_, _>>().await?;
+            if is_finalized(&mut self.client, *block).await? {
+                let value: Arc<[ModuleRef]> = Arc::from(modules.clone());
+                self.cache.insert(key, CacheValue::ModuleList(value));
+            }
+            Ok(QueryResponse {
+                block_hash: result.block_hash,
+                response:   modules,
+            })
+        } else {
+            let result = self.client.get_module_list(bi).await?;
+            let modules: Vec<ModuleRef> = result.response.collect::<Result<_, _>>().await?;
+            Ok(QueryResponse {
+                block_hash: result.block_hash,
+                response:   modules,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(b: u8) -> BlockHash { [b; 32].into() }
+
+    #[test]
+    fn contains_finalized_true_when_present() {
+        let ancestors = [hash(9), hash(8), hash(7)];
+        assert!(contains_finalized(&ancestors, hash(8)));
+    }
+
+    #[test]
+    fn contains_finalized_false_when_absent() {
+        let ancestors = [hash(9), hash(8), hash(7)];
+        assert!(!contains_finalized(&ancestors, hash(1)));
+    }
+
+    #[test]
+    fn contains_finalized_false_on_empty_window() {
+        assert!(!contains_finalized(&[], hash(1)));
+    }
+}
@@ -0,0 +1,92 @@
+//! Per-request configuration for [`Client`](super::Client) calls.
+//!
+//! By default every method on [`Client`] sends its `tonic::Request` with no
+//! deadline and no way to cancel it once issued. [`RequestContext`] carries
+//! that configuration explicitly so callers that need it can opt in via the
+//! `*_with_context` variant of a method, while the plain method keeps
+//! behaving as before (it simply uses [`RequestContext::default`]).
+use std::time::Duration;
+
+use tonic::metadata::{Ascii, MetadataKey, MetadataValue};
+
+/// Configuration attached to a single RPC: a deadline, an optional
+/// cancellation token, and arbitrary extra metadata.
+///
+/// A `RequestContext` is cheap to build and is consumed by reference, so the
+/// same one can be reused across multiple calls, e.g. to give a batch of
+/// requests a shared deadline.
+#[derive(Clone, Debug, Default)]
+pub struct RequestContext {
+    deadline:       Option<Duration>,
+    cancellation:   Option<tokio_util::sync::CancellationToken>,
+    extra_metadata: Vec<(MetadataKey<Ascii>, MetadataValue<Ascii>)>,
+}
+
+impl RequestContext {
+    /// A context with no deadline, no cancellation, and no extra metadata,
+    /// i.e. the same behaviour as not providing a context at all.
+    pub fn new() -> Self { Self::default() }
+
+    /// Bound how long the request is allowed to take. This sets the `tonic`
+    /// client-side timeout, which also causes a `grpc-timeout` header to be
+    /// sent so the server can abort the corresponding server-side work once
+    /// the deadline passes.
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Attach a cancellation token. Cancelling the token stops the in-flight
+    /// call early, completing it with a [`tonic::Code::Cancelled`] error.
+    /// A stream already returned by a streaming endpoint simply ends once
+    /// the token fires, without a final error item.
+    pub fn with_cancellation(mut self, token: tokio_util::sync::CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Attach an extra metadata entry to send with the request. Invalid
+    /// ASCII metadata keys or values are silently dropped rather than
+    /// turning context construction into a fallible operation.
+    pub fn with_metadata(mut self, key: &str, value: impl AsRef<str>) -> Self {
+        if let (Ok(key), Ok(value)) = (
+            MetadataKey::from_bytes(key.as_bytes()),
+            MetadataValue::try_from(value.as_ref()),
+        ) {
+            self.extra_metadata.push((key, value));
+        }
+        self
+    }
+
+    /// Apply this context's deadline and extra metadata to `request`.
+    pub(crate) fn apply<T>(&self, mut request: tonic::Request<T>) -> tonic::Request<T> {
+        if let Some(deadline) = self.deadline {
+            request.set_timeout(deadline);
+        }
+        for (key, value) in &self.extra_metadata {
+            request.metadata_mut().insert(key.clone(), value.clone());
+        }
+        request
+    }
+
+    pub(crate) fn cancellation(&self) -> Option<&tokio_util::sync::CancellationToken> {
+        self.cancellation.as_ref()
+    }
+}
+
+/// Race `fut` against the context's cancellation token, if any. Returns
+/// `Err(Status::cancelled(..))` if the token fires first.
+pub(crate) async fn with_cancellation<T>(
+    ctx: &RequestContext,
+    fut: impl std::future::Future<Output = Result<T, tonic::Status>>,
+) -> Result<T, tonic::Status> {
+    match ctx.cancellation() {
+        Some(token) => {
+            tokio::select! {
+                result = fut => result,
+                _ = token.cancelled() => Err(tonic::Status::cancelled("request cancelled by caller")),
+            }
+        }
+        None => fut.await,
+    }
+}
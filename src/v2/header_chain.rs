@@ -0,0 +1,393 @@
+//! A compact, queryable index of the finalized header chain, suitable for a
+//! light client.
+//!
+//! [`HeaderChain`] consumes [`Client::subscribe_finalized_blocks`] and keeps
+//! enough state to answer "what is the block hash at height H?" and "is X an
+//! ancestor of Y?" without a round-trip per query, while staying
+//! memory-bounded: every [`SECTION_SIZE`] consecutive heights are grouped
+//! into a *section*, and once a section is complete its individual headers
+//! are dropped from RAM in favour of a single Merkle root over the section's
+//! block hashes. A [`HeaderChain::prove_block_at`] for a height in a sealed
+//! section re-derives the section's headers on demand (via
+//! [`Client::get_ancestors`]) and returns a branch that can be checked
+//! offline against the stored root with [`verify_proof`].
+use std::collections::{BTreeMap, HashMap};
+
+use futures::StreamExt;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    endpoints,
+    types::{hashes::BlockHash, AbsoluteBlockHeight},
+};
+
+use super::{BlockIdentifier, Client, FinalizedBlockInfo};
+
+/// Number of heights grouped into a single Merkle section.
+pub const SECTION_SIZE: u64 = 2048;
+
+/// A Merkle branch from a leaf up to a section root: one sibling hash per
+/// level, ordered from the leaf's level to the root.
+pub type MerkleBranch = Vec<BlockHash>;
+
+struct State {
+    /// Height of the very first header ever indexed, i.e. the start of
+    /// section 0. `None` until the first header arrives.
+    genesis_height: Option<AbsoluteBlockHeight>,
+    /// The most recently indexed header.
+    best: Option<FinalizedBlockInfo>,
+    /// Merkle roots of sealed sections, indexed by section number.
+    section_roots: Vec<BlockHash>,
+    /// Last block hash of each sealed section, used as a starting point to
+    /// re-fetch that section's headers on demand.
+    section_tips: Vec<BlockHash>,
+    /// Block hashes of the current, not-yet-sealed section, in ascending
+    /// height order.
+    current_section: Vec<BlockHash>,
+    /// Headers of the current section, for fast `header_at`/ancestry
+    /// lookups. Cleared whenever the current section seals.
+    height_index: BTreeMap<AbsoluteBlockHeight, FinalizedBlockInfo>,
+    hash_index:   HashMap<BlockHash, AbsoluteBlockHeight>,
+}
+
+impl State {
+    fn new() -> Self {
+        Self {
+            genesis_height:   None,
+            best:             None,
+            section_roots:    Vec::new(),
+            section_tips:     Vec::new(),
+            current_section:  Vec::new(),
+            height_index:     BTreeMap::new(),
+            hash_index:       HashMap::new(),
+        }
+    }
+
+    fn push(&mut self, info: FinalizedBlockInfo) {
+        if self.genesis_height.is_none() {
+            self.genesis_height = Some(info.height);
+        }
+        self.best = Some(info);
+        self.height_index.insert(info.height, info);
+        self.hash_index.insert(info.block_hash, info.height);
+        self.current_section.push(info.block_hash);
+
+        if self.current_section.len() as u64 == SECTION_SIZE {
+            let root = merkle_root(&self.current_section);
+            self.section_roots.push(root);
+            self.section_tips
+                .push(*self.current_section.last().expect("non-empty section"));
+            for hash in self.current_section.drain(..) {
+                if let Some(height) = self.hash_index.remove(&hash) {
+                    self.height_index.remove(&height);
+                }
+            }
+        }
+    }
+
+    /// The section number and offset within that section for `height`,
+    /// relative to the genesis height.
+    fn locate(&self, height: AbsoluteBlockHeight) -> Option<(usize, usize)> {
+        let genesis = self.genesis_height?.height;
+        let offset = height.height.checked_sub(genesis)?;
+        Some(((offset / SECTION_SIZE) as usize, (offset % SECTION_SIZE) as usize))
+    }
+}
+
+/// A compact, verifiable, incrementally-built index of the finalized header
+/// chain. See the module documentation for details.
+pub struct HeaderChain {
+    client: Client,
+    state:  parking_lot::RwLock<State>,
+}
+
+impl HeaderChain {
+    /// Construct an empty header chain backed by `client`.
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            state: parking_lot::RwLock::new(State::new()),
+        }
+    }
+
+    /// Consume [`Client::subscribe_finalized_blocks`] starting at `from`,
+    /// indexing every header as it arrives. Runs until the stream ends,
+    /// which (thanks to `subscribe_finalized_blocks`'s own reconnect logic)
+    /// should only happen if the caller drops it.
+    pub async fn run(&self, from: AbsoluteBlockHeight) {
+        let mut stream = Box::pin(self.client.subscribe_finalized_blocks(from));
+        while let Some(info) = stream.next().await {
+            self.state.write().push(info);
+        }
+    }
+
+    /// The most recently indexed finalized header.
+    pub fn best(&self) -> Option<FinalizedBlockInfo> { self.state.read().best }
+
+    /// The header at `height`, if it is still held in memory (i.e. it
+    /// belongs to the current, unsealed section). Headers in sealed
+    /// sections are no longer held in RAM; use [`Self::prove_block_at`] to
+    /// recover one.
+    pub fn header_at(&self, height: AbsoluteBlockHeight) -> Option<FinalizedBlockInfo> {
+        self.state.read().height_index.get(&height).copied()
+    }
+
+    /// Determine whether `ancestor` is an ancestor of (or equal to)
+    /// `child`, using the in-memory index where possible and falling back
+    /// to [`Client::get_ancestors`] when `child` or `ancestor` is not
+    /// already indexed.
+    pub async fn ancestry(
+        &self,
+        child: BlockHash,
+        ancestor: BlockHash,
+    ) -> endpoints::QueryResult<bool> {
+        if child == ancestor {
+            return Ok(true);
+        }
+        let heights = {
+            let state = self.state.read();
+            let ancestor_height = state.hash_index.get(&ancestor).copied();
+            let child_height = state.hash_index.get(&child).copied();
+            (ancestor_height, child_height)
+        };
+        if let (Some(ancestor_height), Some(child_height)) = heights {
+            return Ok(ancestor_height <= child_height
+                && self.header_at(ancestor_height).map(|h| h.block_hash) == Some(ancestor));
+        }
+        // `child` or `ancestor` is not in the in-memory index, which (since
+        // the current section is always kept) means it belongs to a sealed
+        // section or isn't part of our chain at all. Walk ancestors in
+        // `SECTION_SIZE`-sized pages, the same granularity
+        // `prove_block_at` re-fetches a sealed section at, rather than one
+        // unbounded request for the whole history.
+        let mut client = self.client.clone();
+        let mut frontier = child;
+        loop {
+            let response = client
+                .get_ancestors(&BlockIdentifier::Given(frontier), SECTION_SIZE)
+                .await?;
+            let hashes: Vec<BlockHash> =
+                response.response.collect::<Vec<_>>().await.into_iter().collect::<Result<_, _>>()?;
+            if hashes.iter().any(|hash| *hash == ancestor) {
+                return Ok(true);
+            }
+            if (hashes.len() as u64) < SECTION_SIZE {
+                // Reached the start of the chain without finding `ancestor`.
+                return Ok(false);
+            }
+            frontier = *hashes.last().expect("checked length above");
+        }
+    }
+
+    /// Produce a Merkle proof that the block at `height` has hash `hash`,
+    /// verifiable against the section root returned alongside it with
+    /// [`verify_proof`]. Re-fetches the section's headers from the node if
+    /// they are no longer held in memory.
+    ///
+    /// Returns the leaf's hash, the section root, the leaf's index within
+    /// the section (needed by [`verify_proof`], since sections are
+    /// relative to the chain's `genesis_height` rather than absolute
+    /// height), and the branch itself.
+    pub async fn prove_block_at(
+        &self,
+        height: AbsoluteBlockHeight,
+    ) -> endpoints::QueryResult<Option<(BlockHash, BlockHash, usize, MerkleBranch)>> {
+        let Some((section, offset)) = self.state.read().locate(height) else {
+            return Ok(None);
+        };
+        let sealed_sections = self.state.read().section_roots.len();
+        let leaves = if section < sealed_sections {
+            let tip = self.state.read().section_tips[section];
+            let mut client = self.client.clone();
+            let response = client
+                .get_ancestors(&BlockIdentifier::Given(tip), SECTION_SIZE)
+                .await?;
+            let mut hashes: Vec<BlockHash> =
+                response.response.collect::<Vec<_>>().await.into_iter().collect::<Result<_, _>>()?;
+            hashes.reverse();
+            hashes
+        } else if section == sealed_sections {
+            self.state.read().current_section.clone()
+        } else {
+            return Ok(None);
+        };
+        if offset >= leaves.len() {
+            return Ok(None);
+        }
+        let hash = leaves[offset];
+        let (root, branch) = merkle_root_and_branch(&leaves, offset);
+        Ok(Some((hash, root, offset, branch)))
+    }
+}
+
+/// Combine two sibling hashes into their parent, the way [`merkle_root`]
+/// does.
+fn combine(left: &BlockHash, right: &BlockHash) -> BlockHash {
+    let mut hasher = Sha256::new();
+    hasher.update(left.as_ref());
+    hasher.update(right.as_ref());
+    let digest: [u8; 32] = hasher.finalize().into();
+    digest.into()
+}
+
+/// Compute the Merkle root of `leaves`, pairing them up level by level and
+/// duplicating the last leaf of a level when its count is odd.
+fn merkle_root(leaves: &[BlockHash]) -> BlockHash {
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        let mut next = Vec::with_capacity((level.len() + 1) / 2);
+        for pair in level.chunks(2) {
+            let right = pair.get(1).unwrap_or(&pair[0]);
+            next.push(combine(&pair[0], right));
+        }
+        level = next;
+    }
+    level[0]
+}
+
+/// Like [`merkle_root`], but also returns the branch (sibling hashes from
+/// the leaf's level up to the root) for the leaf at `index`.
+fn merkle_root_and_branch(leaves: &[BlockHash], mut index: usize) -> (BlockHash, MerkleBranch) {
+    let mut level = leaves.to_vec();
+    let mut branch = Vec::new();
+    while level.len() > 1 {
+        let sibling_index = if index % 2 == 0 { index + 1 } else { index - 1 };
+        let sibling = *level.get(sibling_index).unwrap_or(&level[index]);
+        branch.push(sibling);
+
+        let mut next = Vec::with_capacity((level.len() + 1) / 2);
+        for pair in level.chunks(2) {
+            let right = pair.get(1).unwrap_or(&pair[0]);
+            next.push(combine(&pair[0], right));
+        }
+        level = next;
+        index /= 2;
+    }
+    (level[0], branch)
+}
+
+/// Check that `hash` at leaf index `leaf_index` (the section-relative
+/// offset returned alongside the branch by
+/// [`HeaderChain::prove_block_at`]) is consistent with section `root`,
+/// given the Merkle `branch`.
+///
+/// `leaf_index` is *not* `height.height % SECTION_SIZE`: sections are
+/// laid out relative to the chain's `genesis_height`, so that equivalence
+/// only holds when `genesis_height` happens to be a multiple of
+/// [`SECTION_SIZE`]. Always use the index `prove_block_at` returned for
+/// this proof rather than recomputing it from the absolute height.
+pub fn verify_proof(root: BlockHash, leaf_index: usize, hash: BlockHash, branch: &[BlockHash]) -> bool {
+    let mut index = leaf_index;
+    let mut current = hash;
+    for sibling in branch {
+        current = if index % 2 == 0 {
+            combine(&current, sibling)
+        } else {
+            combine(sibling, &current)
+        };
+        index /= 2;
+    }
+    current == root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build `n` distinct leaf hashes, numbered `0..n`.
+    fn leaves(n: u8) -> Vec<BlockHash> {
+        (0..n).map(|i| [i; 32].into()).collect()
+    }
+
+    #[test]
+    fn merkle_root_and_branch_round_trip() {
+        for n in [1u8, 2, 3, 7, 8, 9, 16] {
+            let section = leaves(n);
+            let root = merkle_root(&section);
+            for (index, &hash) in section.iter().enumerate() {
+                let (branch_root, branch) = merkle_root_and_branch(&section, index);
+                assert_eq!(branch_root, root, "root mismatch for n={n}, index={index}");
+
+                assert!(
+                    verify_proof(root, index, hash, &branch),
+                    "proof did not verify for n={n}, index={index}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn verify_proof_rejects_wrong_hash() {
+        let section = leaves(5);
+        let root = merkle_root(&section);
+        let (_, branch) = merkle_root_and_branch(&section, 2);
+        let wrong_hash: BlockHash = [0xffu8; 32].into();
+        assert!(!verify_proof(root, 2, wrong_hash, &branch));
+    }
+
+    #[test]
+    fn verify_proof_rejects_wrong_root() {
+        let section = leaves(5);
+        let (_, branch) = merkle_root_and_branch(&section, 2);
+        let wrong_root: BlockHash = [0xffu8; 32].into();
+        assert!(!verify_proof(wrong_root, 2, section[2], &branch));
+    }
+
+    /// Regression test for a bug where `verify_proof` recomputed the leaf
+    /// index as `height.height % SECTION_SIZE`, which only agrees with
+    /// `State::locate`'s section-relative offset when `genesis_height` is
+    /// itself a multiple of `SECTION_SIZE`. A light client that starts
+    /// mid-chain (as any client subscribing from a non-zero `from` does)
+    /// must use the offset `prove_block_at` returns, not one recomputed
+    /// from the absolute height.
+    #[test]
+    fn locate_offset_is_relative_to_genesis_not_absolute_height() {
+        let mut state = State::new();
+        // A genesis height that is deliberately *not* a multiple of
+        // `SECTION_SIZE`, so `height % SECTION_SIZE` disagrees with the
+        // real, genesis-relative offset.
+        let genesis = AbsoluteBlockHeight { height: SECTION_SIZE * 10 + 123 };
+
+        let info = |height: AbsoluteBlockHeight| FinalizedBlockInfo {
+            height,
+            block_hash: [height.height as u8; 32].into(),
+        };
+        state.push(info(genesis));
+        state.push(info(AbsoluteBlockHeight { height: genesis.height + 5 }));
+
+        let (section, offset) = state.locate(AbsoluteBlockHeight { height: genesis.height + 5 }).unwrap();
+        assert_eq!(section, 0);
+        assert_eq!(offset, 5, "offset must be relative to genesis_height");
+        assert_ne!(
+            offset as u64,
+            (genesis.height + 5) % SECTION_SIZE,
+            "this genesis height was chosen so the absolute-height shortcut disagrees with the real offset"
+        );
+    }
+
+    #[test]
+    fn section_seal_root_matches_full_leaf_set() {
+        // Drives `State::push` through one full section: the root stored
+        // when the section seals must match a root recomputed over all
+        // `SECTION_SIZE` leaves, not `SECTION_SIZE - 1` of them (the bug
+        // this regression test guards against).
+        let mut state = State::new();
+        let section: Vec<BlockHash> = (0..SECTION_SIZE)
+            .map(|i| {
+                let mut bytes = [0u8; 32];
+                bytes[..8].copy_from_slice(&i.to_be_bytes());
+                bytes.into()
+            })
+            .collect();
+        for (i, &block_hash) in section.iter().enumerate() {
+            state.push(FinalizedBlockInfo {
+                height: AbsoluteBlockHeight { height: i as u64 },
+                block_hash,
+            });
+        }
+        let sealed_root = state.section_roots[0];
+        let recomputed_from_full_refetch = merkle_root(&section);
+        assert_eq!(sealed_root, recomputed_from_full_refetch);
+        assert_ne!(sealed_root, merkle_root(&section[..section.len() - 1]));
+    }
+}
@@ -0,0 +1,311 @@
+//! Per-endpoint latency and error instrumentation for [`Client`].
+//!
+//! Every [`Client`] carries a [`Metrics`] instance that every endpoint
+//! records into: a request count, an error count broken down by
+//! [`tonic::Code`], and a latency histogram with exponentially spaced
+//! (roughly powers of 1.5) buckets, from which percentiles can be
+//! estimated. Streaming endpoints additionally record time-to-first-item
+//! and per-item inter-arrival time separately from the latency of setting
+//! up the call. Call [`Client::metrics`] for a serializable snapshot, or
+//! [`Client::with_metrics_observer`] to forward individual observations to
+//! your own telemetry as they happen.
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use futures::Stream;
+
+/// The endpoints that record metrics. Kept as an enum (rather than a
+/// `&'static str`) so the per-endpoint counters can live in plain struct
+/// fields instead of behind a hash map lookup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endpoint {
+    AccountInfo,
+    AccountList,
+    ModuleList,
+    Ancestors,
+    FinalizedBlocks,
+}
+
+impl Endpoint {
+    fn name(self) -> &'static str {
+        match self {
+            Endpoint::AccountInfo => "account_info",
+            Endpoint::AccountList => "account_list",
+            Endpoint::ModuleList => "module_list",
+            Endpoint::Ancestors => "ancestors",
+            Endpoint::FinalizedBlocks => "finalized_blocks",
+        }
+    }
+}
+
+/// Growth factor between consecutive histogram buckets.
+const BUCKET_GROWTH: f64 = 1.5;
+/// Number of buckets, spanning from 1ms to roughly `1.5^63` ms, i.e. far
+/// beyond any timeout this client would reasonably be configured with.
+const NUM_BUCKETS: usize = 64;
+
+/// A log-bucketed latency histogram. Buckets are exponentially spaced
+/// (roughly powers of [`BUCKET_GROWTH`] starting at 1ms), and `min`/`max`/
+/// `count`/`sum` are tracked alongside so percentiles can be estimated from
+/// the snapshot.
+struct Histogram {
+    buckets:     Vec<AtomicU64>,
+    count:       AtomicU64,
+    sum_micros:  AtomicU64,
+    min_micros:  AtomicU64,
+    max_micros:  AtomicU64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Self {
+            buckets:    (0..NUM_BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            count:      AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+            min_micros: AtomicU64::new(u64::MAX),
+            max_micros: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, elapsed: Duration) {
+        let micros = elapsed.as_micros().min(u64::MAX as u128).max(1) as u64;
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+        self.min_micros.fetch_min(micros, Ordering::Relaxed);
+        self.max_micros.fetch_max(micros, Ordering::Relaxed);
+        self.buckets[bucket_for(micros)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> HistogramSnapshot {
+        let count = self.count.load(Ordering::Relaxed);
+        HistogramSnapshot {
+            count,
+            sum_micros: self.sum_micros.load(Ordering::Relaxed),
+            min_micros: if count == 0 {
+                0
+            } else {
+                self.min_micros.load(Ordering::Relaxed)
+            },
+            max_micros: self.max_micros.load(Ordering::Relaxed),
+            buckets: self
+                .buckets
+                .iter()
+                .map(|b| b.load(Ordering::Relaxed))
+                .collect(),
+        }
+    }
+}
+
+fn bucket_for(micros: u64) -> usize {
+    let ms = (micros as f64 / 1000.0).max(1e-3);
+    let idx = (ms.ln() / BUCKET_GROWTH.ln()).floor();
+    if !idx.is_finite() || idx < 0.0 {
+        0
+    } else {
+        (idx as usize).min(NUM_BUCKETS - 1)
+    }
+}
+
+/// A point-in-time snapshot of a [`Histogram`].
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct HistogramSnapshot {
+    pub count:      u64,
+    pub sum_micros: u64,
+    pub min_micros: u64,
+    pub max_micros: u64,
+    /// Counts per bucket; bucket `i` covers roughly
+    /// `[1.5^i, 1.5^(i+1))` milliseconds.
+    pub buckets:    Vec<u64>,
+}
+
+/// Per-endpoint counters and histograms.
+struct PerEndpoint {
+    requests:          AtomicU64,
+    /// Indexed by `tonic::Code as i32`; there are 17 defined codes.
+    errors_by_code:    Vec<AtomicU64>,
+    latency:           Histogram,
+    time_to_first_item: Histogram,
+    inter_arrival:     Histogram,
+}
+
+impl PerEndpoint {
+    fn new() -> Self {
+        Self {
+            requests:           AtomicU64::new(0),
+            errors_by_code:     (0..17).map(|_| AtomicU64::new(0)).collect(),
+            latency:            Histogram::new(),
+            time_to_first_item: Histogram::new(),
+            inter_arrival:      Histogram::new(),
+        }
+    }
+
+    fn record_error(&self, code: tonic::Code) {
+        let idx = code as i32 as usize;
+        if let Some(counter) = self.errors_by_code.get(idx) {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn snapshot(&self, name: &'static str) -> EndpointSnapshot {
+        EndpointSnapshot {
+            endpoint:           name,
+            requests:           self.requests.load(Ordering::Relaxed),
+            errors_by_code:     self
+                .errors_by_code
+                .iter()
+                .enumerate()
+                .map(|(code, count)| (code as i32, count.load(Ordering::Relaxed)))
+                .filter(|(_, count)| *count > 0)
+                .collect(),
+            latency:            self.latency.snapshot(),
+            time_to_first_item: self.time_to_first_item.snapshot(),
+            inter_arrival:      self.inter_arrival.snapshot(),
+        }
+    }
+}
+
+/// A point-in-time snapshot of the counters for a single endpoint.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct EndpointSnapshot {
+    pub endpoint:           &'static str,
+    pub requests:           u64,
+    /// `(tonic::Code as i32, count)` pairs, omitting codes that never
+    /// occurred.
+    pub errors_by_code:     Vec<(i32, u64)>,
+    pub latency:            HistogramSnapshot,
+    /// Only populated for streaming endpoints.
+    pub time_to_first_item: HistogramSnapshot,
+    /// Only populated for streaming endpoints.
+    pub inter_arrival:      HistogramSnapshot,
+}
+
+/// A callback invoked with every request observation (for streaming
+/// endpoints, the call setup), in addition to it being folded into the
+/// [`Client::metrics`] snapshot. Per-item stream timings (time-to-first-item
+/// and inter-arrival) are only folded into the snapshot and are not passed
+/// to the observer. Use this to forward observations into an existing
+/// telemetry pipeline.
+pub trait MetricsObserver: Send + Sync {
+    fn observe(&self, endpoint: &'static str, elapsed: Duration, status: Result<(), tonic::Code>);
+}
+
+/// The metrics collected by a [`Client`]. Cheaply clonable (it is only ever
+/// held behind an `Arc`) and shared between clones of the same `Client`.
+#[derive(Default)]
+pub(crate) struct Metrics {
+    account_info:     OnceEndpoint,
+    account_list:     OnceEndpoint,
+    module_list:      OnceEndpoint,
+    ancestors:        OnceEndpoint,
+    finalized_blocks: OnceEndpoint,
+    observer:         Option<Arc<dyn MetricsObserver>>,
+}
+
+/// A thin newtype so `Metrics` can derive `Default` without `PerEndpoint`
+/// needing one (its `Histogram`s do not implement `Default` sensibly, since
+/// `min_micros` must start at `u64::MAX`, not `0`).
+struct OnceEndpoint(PerEndpoint);
+
+impl Default for OnceEndpoint {
+    fn default() -> Self { Self(PerEndpoint::new()) }
+}
+
+impl std::fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Metrics").finish_non_exhaustive()
+    }
+}
+
+impl Metrics {
+    pub(crate) fn set_observer(&mut self, observer: Arc<dyn MetricsObserver>) {
+        self.observer = Some(observer);
+    }
+
+    fn endpoint(&self, e: Endpoint) -> &PerEndpoint {
+        match e {
+            Endpoint::AccountInfo => &self.account_info.0,
+            Endpoint::AccountList => &self.account_list.0,
+            Endpoint::ModuleList => &self.module_list.0,
+            Endpoint::Ancestors => &self.ancestors.0,
+            Endpoint::FinalizedBlocks => &self.finalized_blocks.0,
+        }
+    }
+
+    /// Record the outcome of a unary (or call-setup, for streaming
+    /// endpoints) request.
+    pub(crate) fn record(&self, endpoint: Endpoint, elapsed: Duration, status: Result<(), tonic::Code>) {
+        let per = self.endpoint(endpoint);
+        per.requests.fetch_add(1, Ordering::Relaxed);
+        per.latency.record(elapsed);
+        if let Err(code) = status {
+            per.record_error(code);
+        }
+        if let Some(observer) = &self.observer {
+            observer.observe(endpoint.name(), elapsed, status);
+        }
+    }
+
+    fn record_stream_item(&self, endpoint: Endpoint, elapsed: Duration, first: bool, status: Result<(), tonic::Code>) {
+        let per = self.endpoint(endpoint);
+        if first {
+            per.time_to_first_item.record(elapsed);
+        } else {
+            per.inter_arrival.record(elapsed);
+        }
+        if let Err(code) = status {
+            per.record_error(code);
+        }
+    }
+
+    /// A point-in-time snapshot of every endpoint's counters.
+    pub(crate) fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            endpoints: vec![
+                self.account_info.0.snapshot(Endpoint::AccountInfo.name()),
+                self.account_list.0.snapshot(Endpoint::AccountList.name()),
+                self.module_list.0.snapshot(Endpoint::ModuleList.name()),
+                self.ancestors.0.snapshot(Endpoint::Ancestors.name()),
+                self.finalized_blocks
+                    .0
+                    .snapshot(Endpoint::FinalizedBlocks.name()),
+            ],
+        }
+    }
+}
+
+/// A point-in-time snapshot of a [`Client`]'s metrics, as returned by
+/// [`Client::metrics`].
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct MetricsSnapshot {
+    pub endpoints: Vec<EndpointSnapshot>,
+}
+
+/// Wrap `inner` so that the time from the stream's first poll to its first
+/// item,
+/// and the inter-arrival time between every subsequent pair of items, are
+/// recorded against `endpoint`.
+pub(crate) fn instrument_stream<T>(
+    metrics: Arc<Metrics>,
+    endpoint: Endpoint,
+    inner: impl Stream<Item = Result<T, tonic::Status>> + Send + 'static,
+) -> impl Stream<Item = Result<T, tonic::Status>> {
+    async_stream::stream! {
+        futures::pin_mut!(inner);
+        let mut last = Instant::now();
+        let mut first = true;
+        while let Some(item) = futures::StreamExt::next(&mut inner).await {
+            let now = Instant::now();
+            let elapsed = now.duration_since(last);
+            let status = item.as_ref().map(|_| ()).map_err(|e| e.code());
+            metrics.record_stream_item(endpoint, elapsed, first, status);
+            first = false;
+            last = now;
+            yield item;
+        }
+    }
+}
@@ -7,9 +7,22 @@ use crate::{
 };
 use concordium_contracts_common::AccountAddress;
 use futures::{Stream, StreamExt};
+use std::{sync::Arc, time::Instant};
 use tonic::IntoRequest;
 
+mod cache;
+mod context;
 mod generated;
+mod header_chain;
+mod metrics;
+mod subscribe;
+
+pub use cache::{CacheMetricsSnapshot, CachingClient, DEFAULT_CACHE_CAPACITY};
+pub use context::RequestContext;
+use context::with_cancellation;
+pub use header_chain::{verify_proof, HeaderChain, MerkleBranch, SECTION_SIZE};
+pub use metrics::{EndpointSnapshot, HistogramSnapshot, MetricsObserver, MetricsSnapshot};
+use metrics::{instrument_stream, Endpoint, Metrics};
 
 #[derive(Clone, Debug)]
 /// Client that can perform queries.
@@ -18,7 +31,8 @@ mod generated;
 /// behind a Mutex, the intended way to use it is to clone it. Cloning is very
 /// cheap and will reuse the underlying connection.
 pub struct Client {
-    client: generated::queries_client::QueriesClient<tonic::transport::Channel>,
+    client:  generated::queries_client::QueriesClient<tonic::transport::Channel>,
+    metrics: Arc<Metrics>,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -128,15 +142,54 @@ impl Client {
         endpoint: E,
     ) -> Result<Self, tonic::transport::Error> {
         let client = generated::queries_client::QueriesClient::connect(endpoint).await?;
-        Ok(Self { client })
+        Ok(Self {
+            client,
+            metrics: Arc::new(Metrics::default()),
+        })
+    }
+
+    /// Forward every request's metrics observation (for streaming endpoints,
+    /// the call setup; per-item stream timings are not forwarded) to
+    /// `observer`, in addition to it being folded into the snapshot returned
+    /// by [`Self::metrics`].
+    /// Since the metrics are shared between clones of a `Client`, this
+    /// affects every clone made from this point onward, but not clones made
+    /// before the call.
+    pub fn with_metrics_observer(mut self, observer: Arc<dyn MetricsObserver>) -> Self {
+        let mut metrics = Metrics::default();
+        metrics.set_observer(observer);
+        self.metrics = Arc::new(metrics);
+        self
     }
 
+    /// A snapshot of the per-endpoint request counts, error counts, and
+    /// latency histograms collected so far.
+    pub fn metrics(&self) -> MetricsSnapshot { self.metrics.snapshot() }
+
     pub async fn get_account_info(
         &mut self,
         acc: &AccountIdentifier,
         bi: &BlockIdentifier,
     ) -> endpoints::QueryResult<QueryResponse<AccountInfo>> {
-        let response = self.client.get_account_info((acc, bi)).await?;
+        self.get_account_info_with_context(acc, bi, &RequestContext::default())
+            .await
+    }
+
+    /// Like [`Self::get_account_info`], but lets the caller bound the
+    /// request with a [`RequestContext`] (deadline, cancellation, extra
+    /// metadata).
+    pub async fn get_account_info_with_context(
+        &mut self,
+        acc: &AccountIdentifier,
+        bi: &BlockIdentifier,
+        ctx: &RequestContext,
+    ) -> endpoints::QueryResult<QueryResponse<AccountInfo>> {
+        let request = ctx.apply((acc, bi).into_request());
+        let start = Instant::now();
+        let result = with_cancellation(ctx, self.client.get_account_info(request)).await;
+        self.metrics
+            .record(Endpoint::AccountInfo, start.elapsed(), status_of(&result));
+        let response = result?;
         let block_hash = extract_metadata(&response)?;
         let response = AccountInfo::try_from(response.into_inner())?;
         Ok(QueryResponse {
@@ -151,9 +204,29 @@ impl Client {
     ) -> endpoints::QueryResult<
         QueryResponse<impl Stream<Item = Result<AccountAddress, tonic::Status>>>,
     > {
-        let response = self.client.get_account_list(bi).await?;
+        self.get_account_list_with_context(bi, &RequestContext::default())
+            .await
+    }
+
+    /// Like [`Self::get_account_list`], but lets the caller bound the
+    /// request with a [`RequestContext`].
+    pub async fn get_account_list_with_context(
+        &mut self,
+        bi: &BlockIdentifier,
+        ctx: &RequestContext,
+    ) -> endpoints::QueryResult<
+        QueryResponse<impl Stream<Item = Result<AccountAddress, tonic::Status>>>,
+    > {
+        let request = ctx.apply(bi.into_request());
+        let start = Instant::now();
+        let result = with_cancellation(ctx, self.client.get_account_list(request)).await;
+        self.metrics
+            .record(Endpoint::AccountList, start.elapsed(), status_of(&result));
+        let response = result?;
         let block_hash = extract_metadata(&response)?;
         let stream = response.into_inner().map(|x| x.and_then(TryFrom::try_from));
+        let stream = instrument_stream(self.metrics.clone(), Endpoint::AccountList, stream);
+        let stream = cancellable_stream(ctx, stream);
         Ok(QueryResponse {
             block_hash,
             response: stream,
@@ -165,9 +238,28 @@ impl Client {
         bi: &BlockIdentifier,
     ) -> endpoints::QueryResult<QueryResponse<impl Stream<Item = Result<ModuleRef, tonic::Status>>>>
     {
-        let response = self.client.get_module_list(bi).await?;
+        self.get_module_list_with_context(bi, &RequestContext::default())
+            .await
+    }
+
+    /// Like [`Self::get_module_list`], but lets the caller bound the request
+    /// with a [`RequestContext`].
+    pub async fn get_module_list_with_context(
+        &mut self,
+        bi: &BlockIdentifier,
+        ctx: &RequestContext,
+    ) -> endpoints::QueryResult<QueryResponse<impl Stream<Item = Result<ModuleRef, tonic::Status>>>>
+    {
+        let request = ctx.apply(bi.into_request());
+        let start = Instant::now();
+        let result = with_cancellation(ctx, self.client.get_module_list(request)).await;
+        self.metrics
+            .record(Endpoint::ModuleList, start.elapsed(), status_of(&result));
+        let response = result?;
         let block_hash = extract_metadata(&response)?;
         let stream = response.into_inner().map(|x| x.and_then(TryFrom::try_from));
+        let stream = instrument_stream(self.metrics.clone(), Endpoint::ModuleList, stream);
+        let stream = cancellable_stream(ctx, stream);
         Ok(QueryResponse {
             block_hash,
             response: stream,
@@ -180,9 +272,29 @@ impl Client {
         amount: u64,
     ) -> endpoints::QueryResult<QueryResponse<impl Stream<Item = Result<BlockHash, tonic::Status>>>>
     {
-        let response = self.client.get_ancestors((bi, amount)).await?;
+        self.get_ancestors_with_context(bi, amount, &RequestContext::default())
+            .await
+    }
+
+    /// Like [`Self::get_ancestors`], but lets the caller bound the request
+    /// with a [`RequestContext`].
+    pub async fn get_ancestors_with_context(
+        &mut self,
+        bi: &BlockIdentifier,
+        amount: u64,
+        ctx: &RequestContext,
+    ) -> endpoints::QueryResult<QueryResponse<impl Stream<Item = Result<BlockHash, tonic::Status>>>>
+    {
+        let request = ctx.apply((bi, amount).into_request());
+        let start = Instant::now();
+        let result = with_cancellation(ctx, self.client.get_ancestors(request)).await;
+        self.metrics
+            .record(Endpoint::Ancestors, start.elapsed(), status_of(&result));
+        let response = result?;
         let block_hash = extract_metadata(&response)?;
         let stream = response.into_inner().map(|x| x.and_then(TryFrom::try_from));
+        let stream = instrument_stream(self.metrics.clone(), Endpoint::Ancestors, stream);
+        let stream = cancellable_stream(ctx, stream);
         Ok(QueryResponse {
             block_hash,
             response: stream,
@@ -192,10 +304,26 @@ impl Client {
     pub async fn get_finalized_blocks(
         &mut self,
     ) -> endpoints::QueryResult<impl Stream<Item = Result<FinalizedBlockInfo, tonic::Status>>> {
-        let response = self
-            .client
-            .get_finalized_blocks(generated::Empty::default())
-            .await?;
+        self.get_finalized_blocks_with_context(&RequestContext::default())
+            .await
+    }
+
+    /// Like [`Self::get_finalized_blocks`], but lets the caller bound the
+    /// request with a [`RequestContext`]. Cancelling the context's token
+    /// stops the stream early instead of waiting for the node to close it.
+    pub async fn get_finalized_blocks_with_context(
+        &mut self,
+        ctx: &RequestContext,
+    ) -> endpoints::QueryResult<impl Stream<Item = Result<FinalizedBlockInfo, tonic::Status>>> {
+        let request = ctx.apply(tonic::Request::new(generated::Empty::default()));
+        let start = Instant::now();
+        let result = with_cancellation(ctx, self.client.get_finalized_blocks(request)).await;
+        self.metrics.record(
+            Endpoint::FinalizedBlocks,
+            start.elapsed(),
+            status_of(&result),
+        );
+        let response = result?;
         let stream = response.into_inner().map(|x| match x {
             Ok(v) => {
                 let block_hash = v.hash.require_owned().and_then(TryFrom::try_from)?;
@@ -204,10 +332,34 @@ impl Client {
             }
             Err(x) => Err(x),
         });
+        let stream = instrument_stream(self.metrics.clone(), Endpoint::FinalizedBlocks, stream);
+        let stream = cancellable_stream(ctx, stream);
         Ok(stream)
     }
 }
 
+/// Map a `Result<tonic::Response<T>, tonic::Status>` (i.e. the outcome of
+/// the raw gRPC call, before any response parsing) to the `(ok, error
+/// code)` shape [`Metrics::record`] expects.
+fn status_of<T>(result: &Result<tonic::Response<T>, tonic::Status>) -> Result<(), tonic::Code> {
+    match result {
+        Ok(_) => Ok(()),
+        Err(status) => Err(status.code()),
+    }
+}
+
+/// Stop delivering items from `stream` once `ctx`'s cancellation token (if
+/// any) fires.
+fn cancellable_stream<S: Stream + Send + 'static>(
+    ctx: &RequestContext,
+    stream: S,
+) -> std::pin::Pin<Box<dyn Stream<Item = S::Item> + Send>> {
+    match ctx.cancellation().cloned() {
+        Some(token) => Box::pin(stream.take_until(token.cancelled())),
+        None => Box::pin(stream),
+    }
+}
+
 fn extract_metadata<T>(response: &tonic::Response<T>) -> endpoints::RPCResult<BlockHash> {
     match response.metadata().get("blockhash") {
         Some(bytes) => {
@@ -0,0 +1,200 @@
+//! A reconnecting, gap-free wrapper around [`Client::get_finalized_blocks`].
+//!
+//! The raw stream returned by [`Client::get_finalized_blocks`] ends the
+//! moment the underlying gRPC connection has a problem, and on reconnect the
+//! node only resumes from whatever is currently the best/last-finalized
+//! block, leaving a hole between the last block the caller saw and the first
+//! one pushed after reconnecting. [`Client::subscribe_finalized_blocks`]
+//! hides both problems: it reconnects with exponential backoff, and
+//! backfills any gap by walking ancestors before resuming live delivery, so
+//! callers see an in-order, gap-free, at-least-once feed of finalized
+//! blocks.
+use std::time::Duration;
+
+use async_stream::stream;
+use futures::{Stream, StreamExt};
+
+use crate::{
+    endpoints,
+    types::{hashes::BlockHash, AbsoluteBlockHeight},
+};
+
+use super::{BlockIdentifier, Client, FinalizedBlockInfo};
+
+/// Initial delay before the first reconnect attempt. Doubled after every
+/// failed attempt, up to [`MAX_RECONNECT_BACKOFF`].
+const INITIAL_RECONNECT_BACKOFF: Duration = Duration::from_millis(200);
+/// Upper bound on the reconnect backoff.
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+/// Largest number of ancestors requested in a single `get_ancestors` call
+/// while backfilling a gap. A reconnect can leave a gap spanning the whole
+/// chain (an indexer resuming from height zero, say), so `backfill` walks it
+/// in chunks of this size instead of one unbounded request.
+const BACKFILL_CHUNK_SIZE: u64 = 2048;
+
+impl Client {
+    /// Subscribe to finalized blocks starting at (and including) `from`.
+    ///
+    /// The returned stream transparently reconnects on transport errors
+    /// (with exponential backoff), and on reconnect backfills any blocks
+    /// finalized between the last one delivered and the first one the node
+    /// pushes after reconnecting, so every height from `from` onward is
+    /// emitted exactly once, in order.
+    pub fn subscribe_finalized_blocks(
+        &self,
+        from: AbsoluteBlockHeight,
+    ) -> impl Stream<Item = FinalizedBlockInfo> + '_ {
+        stream! {
+            let mut client = self.clone();
+            let mut next_height = from;
+            let mut backoff = INITIAL_RECONNECT_BACKOFF;
+            loop {
+                let mut inner = match client.get_finalized_blocks().await {
+                    Ok(inner) => {
+                        backoff = INITIAL_RECONNECT_BACKOFF;
+                        inner
+                    }
+                    Err(_) => {
+                        tokio::time::sleep(backoff).await;
+                        backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+                        continue;
+                    }
+                };
+                loop {
+                    match inner.next().await {
+                        Some(Ok(info)) => {
+                            if info.height < next_height {
+                                // Already delivered (or before where we started); skip.
+                                continue;
+                            }
+                            if info.height > next_height {
+                                match backfill(&mut client, next_height, &info).await {
+                                    Ok(missing) => {
+                                        // `backfill` is expected to cover every height in
+                                        // `next_height..info.height`; if it doesn't (e.g. the
+                                        // node pruned an ancestor), don't advance past the gap
+                                        // below, so the next reconnect retries it.
+                                        if missing.len() as u64 != info.height.height - next_height.height {
+                                            break;
+                                        }
+                                        for item in missing {
+                                            yield item;
+                                        }
+                                    }
+                                    Err(_) => {
+                                        // Could not backfill the gap right now; reconnect and
+                                        // try again rather than silently skipping blocks.
+                                        break;
+                                    }
+                                }
+                            }
+                            next_height = AbsoluteBlockHeight {
+                                height: info.height.height + 1,
+                            };
+                            yield info;
+                        }
+                        Some(Err(_)) | None => break,
+                    }
+                }
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+            }
+        }
+    }
+}
+
+/// Fetch the finalized blocks in `from..to.height`, in ascending order of
+/// height, by walking ancestors of `to` in chunks of at most
+/// [`BACKFILL_CHUNK_SIZE`]. Stops early (returning fewer blocks than the gap
+/// spans) if the node runs out of ancestors to offer before the gap is
+/// covered; the caller is expected to check the returned length against the
+/// gap size before treating it as fully backfilled.
+async fn backfill(
+    client: &mut Client,
+    from: AbsoluteBlockHeight,
+    to: &FinalizedBlockInfo,
+) -> endpoints::QueryResult<Vec<FinalizedBlockInfo>> {
+    if to.height <= from {
+        return Ok(Vec::new());
+    }
+    let mut remaining = to.height.height - from.height;
+    let mut frontier = to.block_hash;
+    // Collected ancestors, in descending order of height, ending just
+    // before `to`.
+    let mut descending: Vec<BlockHash> = Vec::with_capacity(remaining as usize);
+    while remaining > 0 {
+        let amount = chunk_request_amount(remaining);
+        let bi = BlockIdentifier::Given(frontier);
+        let response = client.get_ancestors(&bi, amount).await?;
+        let hashes: Vec<BlockHash> = response
+            .response
+            .skip(1)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<Result<_, _>>()?;
+        if hashes.is_empty() {
+            // The node has no more ancestors to offer (e.g. genesis); the
+            // gap cannot be fully covered.
+            break;
+        }
+        frontier = *hashes.last().expect("checked non-empty above");
+        remaining -= hashes.len() as u64;
+        descending.extend(hashes);
+    }
+    Ok(assign_ascending_heights(to.height.height, descending))
+}
+
+/// How many ancestors to request for a chunk given `remaining` blocks still
+/// needed: at most [`BACKFILL_CHUNK_SIZE`], plus one, since `get_ancestors`
+/// returns the frontier block itself followed by its ancestors.
+fn chunk_request_amount(remaining: u64) -> u64 { remaining.min(BACKFILL_CHUNK_SIZE) + 1 }
+
+/// Pair `descending` (ancestors of `to_height`, nearest first, i.e. in
+/// descending order of height starting at `to_height - 1`) with their
+/// heights and return them in ascending order.
+fn assign_ascending_heights(to_height: u64, descending: Vec<BlockHash>) -> Vec<FinalizedBlockInfo> {
+    let mut result: Vec<FinalizedBlockInfo> = descending
+        .into_iter()
+        .enumerate()
+        .map(|(i, block_hash)| FinalizedBlockInfo {
+            block_hash,
+            height: AbsoluteBlockHeight {
+                height: to_height - 1 - i as u64,
+            },
+        })
+        .collect();
+    result.reverse();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(b: u8) -> BlockHash { [b; 32].into() }
+
+    #[test]
+    fn chunk_request_amount_accounts_for_inclusive_frontier() {
+        assert_eq!(chunk_request_amount(1), 2);
+        assert_eq!(chunk_request_amount(BACKFILL_CHUNK_SIZE), BACKFILL_CHUNK_SIZE + 1);
+        assert_eq!(chunk_request_amount(BACKFILL_CHUNK_SIZE + 1), BACKFILL_CHUNK_SIZE + 1);
+        assert_eq!(chunk_request_amount(BACKFILL_CHUNK_SIZE * 3), BACKFILL_CHUNK_SIZE + 1);
+    }
+
+    #[test]
+    fn assign_ascending_heights_orders_and_numbers_from_to_height() {
+        // Ancestors of height 10, nearest first: 9, 8, 7.
+        let descending = vec![hash(9), hash(8), hash(7)];
+        let infos = assign_ascending_heights(10, descending);
+        let heights: Vec<u64> = infos.iter().map(|i| i.height.height).collect();
+        assert_eq!(heights, vec![7, 8, 9]);
+        assert_eq!(infos[0].block_hash, hash(7));
+        assert_eq!(infos[2].block_hash, hash(9));
+    }
+
+    #[test]
+    fn assign_ascending_heights_empty_gap() {
+        assert!(assign_ascending_heights(10, Vec::new()).is_empty());
+    }
+}